@@ -1,18 +1,25 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::process;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use indicatif::{ProgressBar, ProgressStyle};
 use chrono::{Utc, DateTime, Date, Datelike, TimeZone};
 use failure::{err_msg, Error};
 use hyper::client::connect::Connect;
-use hyper::{Client, Request, Body};
+use hyper::{Client, Request, Body, StatusCode};
 use hyper_tls::HttpsConnector;
 use rusqlite::Connection;
 use rusqlite::types::ToSql;
+use serde::{Deserialize, Deserializer};
 use structopt::StructOpt;
 use tokio::prelude::*;
 use tokio::runtime::Runtime;
+use tokio::timer::Delay;
 use urlencoding::encode;
 use xmltree::Element;
 
@@ -21,14 +28,35 @@ const MAX_TRACKS: u64 = 200;
 const PROGRESS_TEMPLATE: &str = "[{elapsed_precise}] {wide_bar} {pos:>7}/{len:7} ({percent}%)";
 
 #[derive(StructOpt)]
-#[structopt(name = "lastfm-archiver", about = "Archive last.fm listening history.")]
-struct Command {
-    #[structopt(help = "API Key")]
-    api_key: String,
-    #[structopt(help = "Username")]
-    user: String,
-    #[structopt(help = "Database path")]
-    database: PathBuf,
+#[structopt(name = "lastfm-archiver", about = "Archive and explore last.fm listening history.")]
+enum Command {
+    #[structopt(name = "sync", about = "Archive recent listening history into the database")]
+    Sync {
+        #[structopt(help = "API Key")]
+        api_key: String,
+        #[structopt(help = "Username")]
+        user: String,
+        #[structopt(help = "Database path")]
+        database: PathBuf,
+    },
+    #[structopt(name = "query", about = "Run a SQL query against the archived history")]
+    Query {
+        #[structopt(help = "Database path")]
+        database: PathBuf,
+        #[structopt(help = "SQL query, or - to read one from stdin")]
+        sql: String,
+        #[structopt(long = "csv", help = "Print the result set as CSV instead of an aligned table")]
+        csv: bool,
+    },
+    #[structopt(name = "recommend", about = "Suggest new artists based on your top artists")]
+    Recommend {
+        #[structopt(help = "API Key")]
+        api_key: String,
+        #[structopt(help = "Database path")]
+        database: PathBuf,
+        #[structopt(short = "n", long = "count", default_value = "10", help = "Number of artists to show")]
+        count: u32,
+    },
 }
 
 #[derive(Debug)]
@@ -55,7 +83,7 @@ struct Track {
 impl Track {
     fn insert(&self, connection: &Connection) -> Result<(), Error> {
         let mut insert = connection.prepare_cached(r#"
-          INSERT INTO play (
+          INSERT OR IGNORE INTO play (
             time, track_mbid, track_name, artist_mbid, artist_name, album_mbid, album_name
           ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
         )?;
@@ -76,60 +104,121 @@ impl Track {
     }
 }
 
-impl Track {
-    fn build_track(mut track: Element) -> Result<Track, Error> {
-        let artist = track.take_child("artist").ok_or_else(
-            || err_msg("missing artist"),
-        )?;
-        let artist = match artist.text {
-            Some(name) => {
-                let mbid = artist.attributes.get("mbid").cloned().filter(
-                    |mbid| mbid != "",
-                );
-                Some(Artist { mbid, name })
-            }
-            None => None,
-        };
-        let album = track.take_child("album").ok_or_else(
-            || err_msg("missing album"),
-        )?;
-        let album = match album.text {
-            Some(name) => {
-                let mbid = album.attributes.get("mbid").cloned().filter(
-                    |mbid| mbid != "",
-                );
-                Some(Album { mbid, name })
-            }
-            None => None,
-        };
-        let mbid = track
-            .take_child("mbid")
-            .ok_or_else(|| err_msg("missing mbid"))?
-            .text
-            .filter(|mbid| mbid != "");
-        let name = track
-            .take_child("name")
-            .ok_or_else(|| err_msg("missing name"))?
-            .text
-            .ok_or_else(|| err_msg("empty name"))?;
-        let time = track
-            .take_child("date")
-            .ok_or_else(|| err_msg("missing date"))?
-            .attributes
-            .get("uts")
-            .ok_or_else(|| err_msg("missing timestamp"))
-            .and_then(|str| i64::from_str(str).map_err(From::from))
-            .map(|secs| Utc.timestamp(secs, 0))?;
+fn deserialize_mbid<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mbid = String::deserialize(deserializer)?;
+    Ok(if mbid.is_empty() { None } else { Some(mbid) })
+}
+
+fn deserialize_uts<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let uts = String::deserialize(deserializer)?;
+    i64::from_str(&uts)
+        .map(|secs| Utc.timestamp(secs, 0))
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtistJson {
+    #[serde(rename = "#text")]
+    name: String,
+    #[serde(default, deserialize_with = "deserialize_mbid")]
+    mbid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AlbumJson {
+    #[serde(rename = "#text")]
+    name: String,
+    #[serde(default, deserialize_with = "deserialize_mbid")]
+    mbid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DateJson {
+    #[serde(deserialize_with = "deserialize_uts")]
+    uts: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrackJson {
+    artist: ArtistJson,
+    album: AlbumJson,
+    #[serde(default, deserialize_with = "deserialize_mbid")]
+    mbid: Option<String>,
+    name: String,
+    date: Option<DateJson>,
+}
+
+impl TryFrom<TrackJson> for Track {
+    type Error = Error;
+
+    fn try_from(track: TrackJson) -> Result<Track, Error> {
+        let time = track.date.ok_or_else(|| err_msg("missing date"))?.uts;
         Ok(Track {
-            artist,
-            album,
-            mbid,
-            name,
+            artist: Some(Artist { mbid: track.artist.mbid, name: track.artist.name }),
+            album: Some(Album { mbid: track.album.mbid, name: track.album.name }),
+            mbid: track.mbid,
+            name: track.name,
             time,
         })
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct RecentTracksAttrJson {
+    page: String,
+    #[serde(rename = "totalPages")]
+    total_pages: String,
+    total: String,
+}
+
+// last.fm's XML-to-JSON conversion collapses a single-element "track" list down to a
+// bare object instead of a one-element array, so a page with exactly one track (the
+// common case for an incremental sync) doesn't deserialize as a `Vec<TrackJson>`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Vec<T> {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+fn deserialize_one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    OneOrMany::deserialize(deserializer).map(Vec::from)
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentTracksJson {
+    #[serde(default, rename = "track", deserialize_with = "deserialize_one_or_many")]
+    tracks: Vec<TrackJson>,
+    #[serde(rename = "@attr")]
+    attr: RecentTracksAttrJson,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseJson {
+    recenttracks: Option<RecentTracksJson>,
+    error: Option<u32>,
+    message: Option<String>,
+}
+
 #[derive(Debug)]
 struct Response {
     page: u64,
@@ -139,75 +228,109 @@ struct Response {
 }
 
 impl Response {
-    fn build_response(response: Element) -> Result<Response, Error> {
-        let page = response
-            .attributes
-            .get("page")
-            .ok_or_else(|| err_msg("missing page"))
-            .and_then(|str| u64::from_str(str).map_err(From::from))?;
-        let total_pages = response
-            .attributes
-            .get("totalPages")
-            .ok_or_else(|| err_msg("missing totalPages"))
-            .and_then(|str| u64::from_str(str).map_err(From::from))?;
-        let total_tracks = response
-            .attributes
-            .get("total")
-            .ok_or_else(|| err_msg("missing total"))
-            .and_then(|str| u64::from_str(str).map_err(From::from))?;
-        let tracks: Result<Vec<Track>, Error> = response
-            .children
+    fn from_slice(v: &[u8]) -> Result<Response, Error> {
+        let parsed: ResponseJson = serde_json::from_slice(v)?;
+        if let Some(code) = parsed.error {
+            let message = parsed.message.unwrap_or_else(|| "unknown error".to_string());
+            return Err(err_msg(format!("last.fm error {}: {}", code, message)));
+        }
+        let recenttracks = parsed.recenttracks.ok_or_else(|| err_msg("missing recenttracks"))?;
+        let page = u64::from_str(&recenttracks.attr.page)?;
+        let total_pages = u64::from_str(&recenttracks.attr.total_pages)?;
+        let total_tracks = u64::from_str(&recenttracks.attr.total)?;
+        let tracks = recenttracks
+            .tracks
             .into_iter()
-            .filter(move |track| {
-                if let Some(status) = track.attributes.get("nowplaying") {
-                    return status != "true";
-                }
-                return true;
-            })
-            .map(Track::build_track)
-            .collect();
-        let tracks = tracks?;
-        Ok(Response {
-            page,
-            total_pages,
-            total_tracks,
-            tracks,
-        })
+            // the currently playing track has no "date"; it is skipped, not archived
+            .filter(|track| track.date.is_some())
+            .map(Track::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Response { page, total_pages, total_tracks, tracks })
     }
+}
 
-    fn from_slice<'a>(v: &'a [u8]) -> Result<Response, Error> {
-        let mut root = Element::parse(v).map_err(Error::from)?;
-        let status = root.attributes.get("status").ok_or_else(
-            || err_msg("missing status"),
-        )?;
-        match status.as_ref() {
-            "ok" => {
-                Response::build_response(root.take_child("recenttracks").ok_or_else(|| {
-                    err_msg("missing recenttracks")
-                })?)
-            }
-            "failed" => {
-                let error = root.take_child("error")
-                    .ok_or_else(|| err_msg("missing error"))?
-                    .text
-                    .ok_or_else(|| err_msg("missing error message"))?;
-                Err(err_msg(error))
+fn user_agent() -> String {
+    format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+fn build_request(url: String) -> Request<Body> {
+    Request::get(url)
+        .header("User-Agent", user_agent())
+        .body(Body::empty())
+        .unwrap()
+}
+
+const MAX_ATTEMPTS: u32 = 6;
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+fn fetch_once<T>(client: &Client<T>, url: String) -> impl Future<Item = Vec<u8>, Error = Error>
+where
+    T: 'static + Sync + Connect,
+{
+    client.request(build_request(url)).from_err::<Error>().and_then(|response| {
+        let status = response.status();
+        // Always drain the body so the connection can be returned to hyper's keep-alive
+        // pool, even when the status itself is what makes this attempt retryable.
+        response.into_body().concat2().from_err().and_then(move |body| {
+            if is_retryable_status(status) {
+                Err(err_msg(format!("last.fm returned {}", status)))
+            } else {
+                Ok(body.to_vec())
             }
-            _ => Err(err_msg("unknown status")),
+        })
+    })
+}
 
-        }
-    }
+// Retries a transient failure (connection error, 5xx, or a last.fm rate-limit) with
+// exponential backoff; anything surfaced by the body itself (bad API key, malformed
+// schema) is returned as-is and short-circuits the caller.
+fn fetch_with_retry<T>(
+    client: Client<T>,
+    url: String,
+    bar: ProgressBar,
+    attempt: u32,
+) -> Box<Future<Item = Vec<u8>, Error = Error> + Send>
+where
+    T: 'static + Sync + Connect,
+{
+    Box::new(fetch_once(&client, url.clone()).then(move |result| {
+        let result: Box<Future<Item = Vec<u8>, Error = Error> + Send> = match result {
+            Ok(body) => Box::new(future::ok(body)),
+            Err(err) => {
+                if attempt >= MAX_ATTEMPTS {
+                    Box::new(future::err(err))
+                } else {
+                    let backoff = Duration::from_secs(1 << (attempt - 1).min(5));
+                    bar.println(format!(
+                        "{} (attempt {}/{}), retrying in {:?}",
+                        err, attempt, MAX_ATTEMPTS, backoff
+                    ));
+                    Box::new(
+                        Delay::new(Instant::now() + backoff)
+                            .from_err()
+                            .and_then(move |_| fetch_with_retry(client, url, bar, attempt + 1)),
+                    )
+                }
+            }
+        };
+        result
+    }))
 }
 
 fn fetch_tracks<T>(
     client: Client<T>,
     api_key: String,
     user: String,
+    from: Option<i64>,
+    bar: ProgressBar,
 ) -> impl Stream<Item = Response, Error = Error>
 where
     T: 'static + Sync + Connect,
 {
-    let user_agent = format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let from = from.map(|timestamp| format!("&from={}", timestamp)).unwrap_or_default();
     stream::unfold(Some(1), move |next| {
         let next = match next {
             Some(n) => n,
@@ -215,43 +338,117 @@ where
         };
 
         let url = format!(
-            "{}/2.0/?method=user.getrecenttracks&limit={}&user={}&api_key={}&page={}",
+            "{}/2.0/?method=user.getrecenttracks&format=json&limit={}&user={}&api_key={}&page={}{}",
             DOMAIN,
             MAX_TRACKS,
             encode(&user),
             encode(&api_key),
-            next
+            next,
+            from
         );
-        let request = Request::get(url)
-            .header("User-Agent", user_agent.clone())
-            .body(Body::empty())
-            .unwrap();
-        Some(client.request(request).from_err::<Error>().and_then(
-            move |response| {
-                response
-                    .into_body()
-                    .concat2()
-                    .from_err()
-                    .and_then(move |body| Response::from_slice(&body))
-                    .map(move |response| {
-                        let next = if response.page < response.total_pages {
-                            Some(response.page + 1)
-                        } else {
-                            None
-                        };
-                        (response, next)
-                    })
-            },
-        ))
+        Some(
+            fetch_with_retry(client.clone(), url, bar.clone(), 1)
+                .and_then(move |body| Response::from_slice(&body))
+                .map(move |response| {
+                    let next = if response.page < response.total_pages {
+                        Some(response.page + 1)
+                    } else {
+                        None
+                    };
+                    (response, next)
+                }),
+        )
     })
 }
 
+#[derive(Debug)]
+struct SimilarArtist {
+    artist: Artist,
+    similarity: f64,
+}
+
+impl SimilarArtist {
+    fn build_similar_artist(mut artist: Element) -> Result<SimilarArtist, Error> {
+        let name = artist
+            .take_child("name")
+            .ok_or_else(|| err_msg("missing name"))?
+            .text
+            .ok_or_else(|| err_msg("empty name"))?;
+        let mbid = artist
+            .take_child("mbid")
+            .and_then(|element| element.text)
+            .filter(|mbid| mbid != "");
+        let similarity = artist
+            .take_child("match")
+            .ok_or_else(|| err_msg("missing match"))?
+            .text
+            .ok_or_else(|| err_msg("empty match"))
+            .and_then(|str| f64::from_str(&str).map_err(From::from))?;
+        Ok(SimilarArtist { artist: Artist { mbid, name }, similarity })
+    }
+
+    fn from_slice(v: &[u8]) -> Result<Vec<SimilarArtist>, Error> {
+        let mut root = Element::parse(v).map_err(Error::from)?;
+        let status = root.attributes.get("status").ok_or_else(
+            || err_msg("missing status"),
+        )?;
+        match status.as_ref() {
+            "ok" => {
+                root.take_child("similarartists")
+                    .ok_or_else(|| err_msg("missing similarartists"))?
+                    .children
+                    .into_iter()
+                    .map(SimilarArtist::build_similar_artist)
+                    .collect()
+            }
+            "failed" => {
+                let error = root.take_child("error")
+                    .ok_or_else(|| err_msg("missing error"))?
+                    .text
+                    .ok_or_else(|| err_msg("missing error message"))?;
+                Err(err_msg(error))
+            }
+            _ => Err(err_msg("unknown status")),
+        }
+    }
+}
+
+fn fetch_similar_artists<T>(
+    client: Client<T>,
+    api_key: String,
+    artist_name: String,
+    bar: ProgressBar,
+) -> impl Future<Item = Vec<SimilarArtist>, Error = Error>
+where
+    T: 'static + Sync + Connect,
+{
+    let url = format!(
+        "{}/2.0/?method=artist.getsimilar&artist={}&api_key={}",
+        DOMAIN,
+        encode(&artist_name),
+        encode(&api_key)
+    );
+    fetch_with_retry(client, url, bar, 1).and_then(|body| SimilarArtist::from_slice(&body))
+}
+
 fn setup_database(connection: &Connection) -> Result<(), Error> {
     connection
         .execute_batch(include_str!("schema.sql"))
         .map_err(From::from)
 }
 
+fn last_played_time(connection: &Connection) -> Result<Option<i64>, Error> {
+    match connection.query_row(
+        "SELECT time FROM play ORDER BY time DESC LIMIT 1",
+        rusqlite::NO_PARAMS,
+        |row| row.get(0),
+    ) {
+        Ok(time) => Ok(Some(time)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
 fn same_month<T: TimeZone>(a: &Date<T>, b: &Date<T>) -> bool {
     a.month() == b.month() && a.year() == b.year()
 }
@@ -267,36 +464,198 @@ where
 {
     let bar = ProgressBar::new(0);
     let mut prev_date = None;
+    let mut bar_length_set = false;
 
     bar.set_style(ProgressStyle::default_bar().template(PROGRESS_TEMPLATE));
 
-    future::result(setup_database(&connection)).and_then(move |_| {
-        fetch_tracks(client, api_key, user).for_each(move |response| {
-            bar.set_length(response.total_tracks);
-            for track in response.tracks.into_iter() {
-                let track_date = track.time.date();
-                if prev_date.is_none() || !same_month(&prev_date.unwrap(), &track_date) {
-                    bar.println(track_date.format("Archiving %B %Y").to_string())
+    future::result(setup_database(&connection))
+        .and_then(move |_| future::result(last_played_time(&connection)).map(|from| (from, connection)))
+        .and_then(move |(from, connection)| {
+            let from = from.map(|time| time + 1);
+            fetch_tracks(client, api_key, user, from, bar.clone()).for_each(move |response| {
+                if !bar_length_set {
+                    bar.set_length(response.total_tracks);
+                    bar_length_set = true;
                 }
-                bar.inc(1);
-                prev_date = Some(track_date);
-                track.insert(&connection)?;
-            }
-            Ok(())
+                for track in response.tracks.into_iter() {
+                    let track_date = track.time.date();
+                    if prev_date.is_none() || !same_month(&prev_date.unwrap(), &track_date) {
+                        bar.println(track_date.format("Archiving %B %Y").to_string())
+                    }
+                    bar.inc(1);
+                    prev_date = Some(track_date);
+                    track.insert(&connection)?;
+                }
+                Ok(())
+            })
         })
-    })
 }
 
-fn process() -> Result<(), Error> {
-    let options = Command::from_args();
+fn sync(api_key: String, user: String, database: PathBuf) -> Result<(), Error> {
     let runtime = Runtime::new()?;
     let https = HttpsConnector::new(num_cpus::get())?;
     let client = Client::builder().build(https);
-    let connection = Connection::open(options.database)?;
-    let archiver = archiver(client, options.api_key, options.user, connection);
+    let connection = Connection::open(database)?;
+    let archiver = archiver(client, api_key, user, connection);
     runtime.block_on_all(archiver)
 }
 
+fn read_sql(sql: String) -> Result<String, Error> {
+    if sql == "-" {
+        let mut buffer = String::new();
+        io::stdin().read_to_string(&mut buffer)?;
+        Ok(buffer)
+    } else {
+        Ok(sql)
+    }
+}
+
+fn format_value(row: &rusqlite::Row, index: usize) -> String {
+    use rusqlite::types::ValueRef;
+    match row.get_raw(index) {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(s) => String::from_utf8_lossy(s).into_owned(),
+        ValueRef::Blob(_) => "<blob>".to_string(),
+    }
+}
+
+fn print_table(rows: &[Vec<String>]) {
+    let columns = rows.first().map(Vec::len).unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+fn print_csv(rows: &[Vec<String>]) {
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .map(|value| {
+                if value.contains(',') || value.contains('"') || value.contains('\n') {
+                    format!("\"{}\"", value.replace('"', "\"\""))
+                } else {
+                    value.clone()
+                }
+            })
+            .collect();
+        println!("{}", line.join(","));
+    }
+}
+
+fn query(database: PathBuf, sql: String, csv: bool) -> Result<(), Error> {
+    let connection = Connection::open(database)?;
+    let sql = read_sql(sql)?;
+    let mut statement = connection.prepare(&sql)?;
+    let columns: Vec<String> = statement.column_names().into_iter().map(String::from).collect();
+    let column_count = columns.len();
+    let mut rows = statement.query(rusqlite::NO_PARAMS)?;
+
+    let mut table = vec![columns];
+    while let Some(row) = rows.next() {
+        let row = row?;
+        table.push((0..column_count).map(|index| format_value(&row, index)).collect());
+    }
+
+    if csv {
+        print_csv(&table);
+    } else {
+        print_table(&table);
+    }
+    Ok(())
+}
+
+const SEED_ARTISTS: u32 = 20;
+const SIMILAR_ARTIST_CONCURRENCY: usize = 4;
+
+fn seed_artists(connection: &Connection, limit: u32) -> Result<Vec<(String, i64)>, Error> {
+    let mut statement = connection.prepare(
+        "SELECT artist_name, COUNT(*) AS plays FROM play GROUP BY artist_name ORDER BY plays DESC LIMIT ?1",
+    )?;
+    let rows = statement.query_map(
+        &[&limit as &ToSql],
+        |row| (row.get::<_, String>(0), row.get::<_, i64>(1)),
+    )?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(From::from)
+}
+
+fn known_artists(connection: &Connection) -> Result<HashSet<String>, Error> {
+    let mut statement = connection.prepare("SELECT DISTINCT artist_name FROM play")?;
+    let rows = statement.query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))?;
+    rows.collect::<Result<HashSet<_>, _>>().map_err(From::from)
+}
+
+fn recommend<T>(
+    client: Client<T>,
+    api_key: String,
+    connection: Connection,
+    count: u32,
+) -> impl Future<Item = (), Error = Error>
+where
+    T: 'static + Sync + Connect,
+{
+    let bar = ProgressBar::new_spinner();
+
+    future::result(seed_artists(&connection, SEED_ARTISTS))
+        .join(future::result(known_artists(&connection)))
+        .and_then(move |(seeds, known)| {
+            let similar = stream::iter_ok(seeds).map(move |(name, plays)| {
+                fetch_similar_artists(client.clone(), api_key.clone(), name, bar.clone()).map(
+                    move |similar_artists| {
+                        similar_artists
+                            .into_iter()
+                            .map(|similar| (similar.artist.name, plays as f64 * similar.similarity))
+                            .collect::<Vec<_>>()
+                    },
+                )
+            });
+            similar
+                .buffer_unordered(SIMILAR_ARTIST_CONCURRENCY)
+                .collect()
+                .map(move |scores| (scores, known))
+        })
+        .map(|(scores, known)| {
+            let mut totals: HashMap<String, f64> = HashMap::new();
+            for (name, score) in scores.into_iter().flatten() {
+                if known.contains(&name) {
+                    continue;
+                }
+                *totals.entry(name).or_insert(0.0) += score;
+            }
+            let mut ranked: Vec<(String, f64)> = totals.into_iter().collect();
+            ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+            for (name, score) in ranked.into_iter().take(count as usize) {
+                println!("{:>8.2}  {}", score, name);
+            }
+        })
+}
+
+fn process() -> Result<(), Error> {
+    match Command::from_args() {
+        Command::Sync { api_key, user, database } => sync(api_key, user, database),
+        Command::Query { database, sql, csv } => query(database, sql, csv),
+        Command::Recommend { api_key, database, count } => {
+            let runtime = Runtime::new()?;
+            let https = HttpsConnector::new(num_cpus::get())?;
+            let client = Client::builder().build(https);
+            let connection = Connection::open(database)?;
+            runtime.block_on_all(recommend(client, api_key, connection, count))
+        }
+    }
+}
+
 fn main() {
     if let Err(err) = process() {
         eprintln!("{}", err);